@@ -6,21 +6,171 @@ use jsonrpsee::core::{async_trait, RpcResult};
 use jsonrpsee::types::error::INVALID_PARAMS_CODE;
 use jsonrpsee::types::ErrorObjectOwned;
 use metrics::{counter, gauge, histogram};
+use serde::{Deserialize, Serialize};
 use solana_client::rpc_client::SerializableTransaction;
+use solana_connection_cache::connection_cache::ConnectionCache;
 use solana_rpc_client_api::config::RpcSendTransactionConfig;
+use solana_sdk::borsh1::try_from_slice_unchecked;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::system_instruction::SystemInstruction;
 use solana_sdk::transaction::VersionedTransaction;
 use solana_transaction_status::UiTransactionEncoding;
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
-use std::time::Duration;
-use tracing::info;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
 const DEFAULT_MINIMUM_TIP: u64 = 1000;
+/// Number of slots a recent blockhash remains valid for, mirroring Solana's
+/// `MAX_PROCESSING_AGE`. Used only as a fallback deadline when the upstream can
+/// no longer report a transaction blockhash's actual `last_valid_block_height`.
+const MAX_PROCESSING_AGE: u64 = 150;
+/// Default compute unit limit applied per non-ComputeBudget instruction when a
+/// transaction sets a compute unit price but no explicit limit, mirroring the
+/// runtime's `DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT`.
+const DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT: u32 = 200_000;
+/// Upper bound on the implicit compute unit budget, mirroring the runtime's
+/// `MAX_COMPUTE_UNIT_LIMIT`.
+const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+/// Number of upcoming leaders a transaction is forwarded to when a leader
+/// schedule is available, matching the default used by Solana's
+/// send-transaction-service.
+const DEFAULT_LEADER_FORWARD_COUNT: u64 = 2;
+/// Size of the per-endpoint QUIC connection pool kept by the TPU connection
+/// cache.
+const TPU_CONNECTION_POOL_SIZE: usize = 4;
+/// Maximum number of in-flight transactions kept in the store. New
+/// submissions are rejected with backpressure once this is reached.
+const DEFAULT_MAX_TRANSACTION_QUEUE_SIZE: usize = 10_000;
+/// JSON-RPC error code returned when the server is applying backpressure.
+const SERVER_IS_BUSY_CODE: i32 = -32097;
+/// Interval between throughput samples taken by the TPS sampler.
+const DEFAULT_TPS_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Rolling throughput view produced by the TPS sampler, returned by the
+/// `get_tps` RPC. Rates are computed as `delta_count / elapsed_seconds`
+/// between the two most recent samples.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TpsStats {
+    /// Transactions accepted per second over the last sampling window.
+    pub submitted_tps: f64,
+    /// Transactions observed landed per second over the last sampling window.
+    pub landed_tps: f64,
+    /// `landed / submitted` over the last window, in `[0.0, 1.0]`.
+    pub landing_rate: f64,
+    /// Current number of transactions held in the store.
+    pub store_depth: u64,
+    /// Cumulative transactions accepted since start.
+    pub submitted_total: u64,
+    /// Cumulative transactions observed landed since start.
+    pub landed_total: u64,
+}
+
+/// Shared cumulative counters fed by the hot paths and read on an interval by
+/// the background sampler, which turns their deltas into a rolling
+/// [`TpsStats`] snapshot.
+#[derive(Default)]
+struct ThroughputSampler {
+    submitted: AtomicU64,
+    landed: AtomicU64,
+    latest: RwLock<TpsStats>,
+}
+
+impl ThroughputSampler {
+    fn record_submitted(&self, count: u64) {
+        self.submitted.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn record_landed(&self, count: u64) {
+        self.landed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> TpsStats {
+        self.latest.read().unwrap().clone()
+    }
+}
+
+/// Forwards serialized transactions straight to the TPU QUIC ports of the
+/// current and upcoming leaders, reusing connections through a shared
+/// [`ConnectionCache`] keyed by the leader `SocketAddr`. Falls back to the
+/// generic sender whenever no leader information is available.
+struct LeaderForwarder {
+    connection_cache: Arc<ConnectionCache>,
+    chain_state: Arc<dyn ChainStateClient>,
+    fallback: Arc<dyn SendTransactionClient>,
+    leader_forward_count: u64,
+}
+
+impl LeaderForwarder {
+    fn new(
+        chain_state: Arc<dyn ChainStateClient>,
+        fallback: Arc<dyn SendTransactionClient>,
+        leader_forward_count: u64,
+    ) -> Self {
+        let connection_cache = Arc::new(ConnectionCache::new_quic(
+            "connection_cache_iris_tpu",
+            TPU_CONNECTION_POOL_SIZE,
+        ));
+        LeaderForwarder {
+            connection_cache,
+            chain_state,
+            fallback,
+            leader_forward_count,
+        }
+    }
+
+    /// Leaders for `slot ..= slot + leader_forward_count` leader rotations,
+    /// deduped and mapped to their TPU sockets by the chain state client.
+    fn leader_tpus(&self) -> Vec<SocketAddr> {
+        let slot = self.chain_state.get_slot();
+        self.chain_state
+            .leader_tpus(slot, self.leader_forward_count)
+    }
+
+    fn forward(&self, wire_transaction: Vec<u8>) {
+        let leaders = self.leader_tpus();
+        if leaders.is_empty() {
+            self.fallback.send_transaction(wire_transaction);
+            return;
+        }
+        // `ConnectionCache::new_quic` hands out a blocking QUIC client, so the
+        // sends must not run on the async ingest worker; push them onto a
+        // blocking task.
+        let connection_cache = self.connection_cache.clone();
+        tokio::task::spawn_blocking(move || {
+            for tpu in leaders {
+                let conn = connection_cache.get_connection(&tpu);
+                if let Err(e) = conn.send_data(&wire_transaction) {
+                    counter!("iris_error", "type" => "tpu_forward_failed").increment(1);
+                    warn!("failed to forward transaction to leader {tpu}: {e}");
+                }
+            }
+        });
+    }
+
+    fn forward_batch(&self, wire_transactions: Vec<Vec<u8>>) {
+        let leaders = self.leader_tpus();
+        if leaders.is_empty() {
+            self.fallback.send_transaction_batch(wire_transactions);
+            return;
+        }
+        let connection_cache = self.connection_cache.clone();
+        tokio::task::spawn_blocking(move || {
+            for tpu in leaders {
+                let conn = connection_cache.get_connection(&tpu);
+                if let Err(e) = conn.send_data_batch(&wire_transactions) {
+                    counter!("iris_error", "type" => "tpu_forward_failed").increment(1);
+                    warn!("failed to forward batch to leader {tpu}: {e}");
+                }
+            }
+        });
+    }
+}
 
 pub struct IrisRpcServerImpl {
-    txn_sender: Arc<dyn SendTransactionClient>,
+    forwarder: Arc<LeaderForwarder>,
     store: Arc<dyn TransactionStore>,
     chain_state: Arc<dyn ChainStateClient>,
     retry_interval: Duration,
@@ -28,6 +178,9 @@ pub struct IrisRpcServerImpl {
     version: VersionResponse,
     tip_address: Option<Pubkey>,
     minimum_tip: Option<u64>,
+    max_queue_size: Option<usize>,
+    enable_preflight: bool,
+    sampler: Arc<ThroughputSampler>,
 }
 
 pub fn invalid_request(reason: &str) -> ErrorObjectOwned {
@@ -38,6 +191,14 @@ pub fn invalid_request(reason: &str) -> ErrorObjectOwned {
     )
 }
 
+pub fn queue_full() -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(
+        SERVER_IS_BUSY_CODE,
+        "Server is busy: transaction queue is full, retry later",
+        None::<String>,
+    )
+}
+
 impl IrisRpcServerImpl {
     pub fn new(
         txn_sender: Arc<dyn SendTransactionClient>,
@@ -49,9 +210,19 @@ impl IrisRpcServerImpl {
         version: VersionResponse,
         tip_address: Option<Pubkey>,
         minimum_tip: Option<u64>,
+        leader_forward_count: Option<u64>,
+        max_queue_size: Option<usize>,
+        enable_preflight: bool,
+        sample_interval: Option<Duration>,
     ) -> Self {
+        let forwarder = Arc::new(LeaderForwarder::new(
+            chain_state.clone(),
+            txn_sender.clone(),
+            leader_forward_count.unwrap_or(DEFAULT_LEADER_FORWARD_COUNT),
+        ));
+        let sampler = Arc::new(ThroughputSampler::default());
         let client = IrisRpcServerImpl {
-            txn_sender,
+            forwarder,
             store,
             chain_state,
             retry_interval,
@@ -59,8 +230,15 @@ impl IrisRpcServerImpl {
             version,
             tip_address,
             minimum_tip,
+            max_queue_size,
+            enable_preflight,
+            sampler,
         };
-        client.spawn_retry_transactions_loop(shutdown);
+        client.spawn_retry_transactions_loop(shutdown.clone());
+        client.spawn_tps_sampler_loop(
+            shutdown,
+            sample_interval.unwrap_or(DEFAULT_TPS_SAMPLE_INTERVAL),
+        );
         client
     }
 
@@ -68,6 +246,21 @@ impl IrisRpcServerImpl {
         self.minimum_tip.unwrap_or(DEFAULT_MINIMUM_TIP)
     }
 
+    fn max_queue_size(&self) -> usize {
+        self.max_queue_size
+            .unwrap_or(DEFAULT_MAX_TRANSACTION_QUEUE_SIZE)
+    }
+
+    /// Deadline past which a transaction can no longer land. Derived from the
+    /// transaction's own blockhash so an already-aged blockhash expires on
+    /// schedule; falls back to `MAX_PROCESSING_AGE` blocks past the ingest
+    /// height when the blockhash is no longer known to the upstream.
+    fn last_valid_block_height(&self, tx: &VersionedTransaction) -> u64 {
+        self.chain_state
+            .get_last_valid_block_height(tx.message.recent_blockhash())
+            .unwrap_or_else(|| self.chain_state.get_block_height() + MAX_PROCESSING_AGE)
+    }
+
     fn pays_minimum_tip(&self, tx: &VersionedTransaction) -> bool {
         // unconfigured tip address, assume all transactions are valid
         if self.tip_address.is_none() { return true };
@@ -98,10 +291,56 @@ impl IrisRpcServerImpl {
         false
     }
 
+    /// Prioritization fee, in lamports, expressed by the transaction's
+    /// ComputeBudget instructions: `compute_unit_price * compute_unit_limit /
+    /// 1_000_000`. When only a compute unit price is set, the limit defaults to
+    /// Solana's implicit budget (`200_000` per non-ComputeBudget instruction,
+    /// capped at `1_400_000`), matching how the runtime derives the fee.
+    /// Returns 0 when the transaction sets no compute unit price.
+    fn priority_fee(tx: &VersionedTransaction) -> u64 {
+        let mut unit_price: Option<u64> = None;
+        let mut unit_limit: Option<u32> = None;
+        let mut non_compute_budget_instructions: u32 = 0;
+
+        for instruction in tx.message.instructions() {
+            let static_keys = tx.message.static_account_keys();
+
+            if let Some(program_id) = static_keys.get(instruction.program_id_index as usize) {
+                if *program_id == solana_sdk::compute_budget::id() {
+                    match try_from_slice_unchecked::<ComputeBudgetInstruction>(&instruction.data) {
+                        Ok(ComputeBudgetInstruction::SetComputeUnitPrice(price)) => {
+                            unit_price = Some(price);
+                        }
+                        Ok(ComputeBudgetInstruction::SetComputeUnitLimit(limit)) => {
+                            unit_limit = Some(limit);
+                        }
+                        _ => {}
+                    }
+                } else {
+                    non_compute_budget_instructions =
+                        non_compute_budget_instructions.saturating_add(1);
+                }
+            }
+        }
+
+        let Some(price) = unit_price else {
+            return 0;
+        };
+        // A transaction that sets no explicit limit still runs against Solana's
+        // default budget, so price it the same way the runtime does.
+        let limit = unit_limit.unwrap_or_else(|| {
+            non_compute_budget_instructions
+                .saturating_mul(DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT)
+                .min(MAX_COMPUTE_UNIT_LIMIT)
+        });
+        (price as u128 * limit as u128 / 1_000_000) as u64
+    }
+
     fn spawn_retry_transactions_loop(&self, shutdown: Arc<AtomicBool>) {
         let store = self.store.clone();
         let chain_state = self.chain_state.clone();
-        let txn_sender = self.txn_sender.clone();
+        let forwarder = self.forwarder.clone();
+        let sampler = self.sampler.clone();
         let retry_interval = self.retry_interval;
 
         tokio::spawn(async move {
@@ -114,6 +353,7 @@ impl IrisRpcServerImpl {
                 let mut transactions_to_remove = vec![];
                 let mut transactions_to_send = vec![];
                 gauge!("iris_retry_transactions").set(transactions_map.len() as f64);
+                let current_block_height = chain_state.get_block_height();
 
                 for mut txn in transactions_map.iter_mut() {
                     if let Some(slot) = chain_state.confirm_signature_status(&txn.key()) {
@@ -122,12 +362,14 @@ impl IrisRpcServerImpl {
                             slot.saturating_sub(txn.slot)
                         );
                         counter!("iris_txn_landed").increment(1);
+                        sampler.record_landed(1);
                         histogram!("iris_txn_slot_latency")
                             .record(slot.saturating_sub(txn.slot) as f64);
                         transactions_to_remove.push(txn.key().clone());
                     }
-                    //check if transaction has been in the store for too long
-                    if txn.value().sent_at.elapsed() > Duration::from_secs(60) {
+                    //the blockhash is dead once the chain advances past its validity window
+                    if current_block_height > txn.value().last_valid_block_height {
+                        counter!("iris_txn_blockhash_expired").increment(1);
                         transactions_to_remove.push(txn.key().clone());
                     }
                     //check if max retries has been reached
@@ -135,11 +377,17 @@ impl IrisRpcServerImpl {
                         transactions_to_remove.push(txn.key().clone());
                     }
                     if txn.retry_count > 0usize {
-                        transactions_to_send.push(txn.wire_transaction.clone());
+                        transactions_to_send
+                            .push((txn.value().priority_fee, txn.wire_transaction.clone()));
                     }
                     txn.retry_count = txn.retry_count.saturating_sub(1);
                 }
 
+                //forward the highest-paying transactions first under load
+                transactions_to_send.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+                let transactions_to_send: Vec<Vec<u8>> =
+                    transactions_to_send.into_iter().map(|(_, wire)| wire).collect();
+
                 gauge!("iris_transactions_removed").increment(transactions_to_remove.len() as f64);
                 for signature in transactions_to_remove {
                     store.remove_transaction(signature);
@@ -150,13 +398,68 @@ impl IrisRpcServerImpl {
                     transactions_to_send.iter().len()
                 );
                 for batches in transactions_to_send.chunks(10) {
-                    txn_sender.send_transaction_batch(batches.to_vec());
+                    forwarder.forward_batch(batches.to_vec());
                 }
 
                 tokio::time::sleep(retry_interval).await;
             }
         });
     }
+
+    fn spawn_tps_sampler_loop(&self, shutdown: Arc<AtomicBool>, sample_interval: Duration) {
+        let store = self.store.clone();
+        let sampler = self.sampler.clone();
+
+        tokio::spawn(async move {
+            let mut last_submitted = sampler.submitted.load(Ordering::Relaxed);
+            let mut last_landed = sampler.landed.load(Ordering::Relaxed);
+            let mut last_sampled_at = Instant::now();
+
+            loop {
+                if shutdown.load(Ordering::Relaxed) {
+                    break;
+                }
+                tokio::time::sleep(sample_interval).await;
+
+                let submitted_total = sampler.submitted.load(Ordering::Relaxed);
+                let landed_total = sampler.landed.load(Ordering::Relaxed);
+                let store_depth = store.get_transactions().len() as u64;
+                let elapsed = last_sampled_at.elapsed().as_secs_f64().max(f64::EPSILON);
+
+                let submitted_delta = submitted_total.saturating_sub(last_submitted);
+                let landed_delta = landed_total.saturating_sub(last_landed);
+                let submitted_tps = submitted_delta as f64 / elapsed;
+                let landed_tps = landed_delta as f64 / elapsed;
+                // Late landings from earlier windows can push `landed_delta`
+                // past `submitted_delta`, so clamp to keep the documented
+                // `[0.0, 1.0]` range.
+                let landing_rate = if submitted_delta > 0 {
+                    (landed_delta as f64 / submitted_delta as f64).min(1.0)
+                } else {
+                    0.0
+                };
+
+                gauge!("iris_submitted_tps").set(submitted_tps);
+                gauge!("iris_landed_tps").set(landed_tps);
+                gauge!("iris_landing_rate").set(landing_rate);
+                gauge!("iris_store_depth").set(store_depth as f64);
+
+                let stats = TpsStats {
+                    submitted_tps,
+                    landed_tps,
+                    landing_rate,
+                    store_depth,
+                    submitted_total,
+                    landed_total,
+                };
+                *sampler.latest.write().unwrap() = stats;
+
+                last_submitted = submitted_total;
+                last_landed = landed_total;
+                last_sampled_at = Instant::now();
+            }
+        });
+    }
 }
 #[async_trait]
 impl IrisRpcServer for IrisRpcServerImpl {
@@ -174,9 +477,13 @@ impl IrisRpcServer for IrisRpcServerImpl {
             counter!("iris_error", "type" => "duplicate_transaction").increment(1);
             return Err(invalid_request("duplicate transaction"));
         }
+        if self.store.get_transactions().len() >= self.max_queue_size() {
+            counter!("iris_error", "type" => "queue_full").increment(1);
+            return Err(queue_full());
+        }
         counter!("iris_txn_total_transactions").increment(1);
         let encoding = params.encoding.unwrap_or(UiTransactionEncoding::Base58);
-        if !params.skip_preflight {
+        if !params.skip_preflight && !self.enable_preflight {
             counter!("iris_error", "type" => "preflight_check").increment(1);
             return Err(invalid_request("running preflight check is not supported"));
         }
@@ -197,7 +504,19 @@ impl IrisRpcServer for IrisRpcServerImpl {
                 }
             };
 
-        if !self.pays_minimum_tip(&versioned_transaction) {
+        // simulate against an upstream node before enqueuing when preflight is requested
+        if !params.skip_preflight {
+            if let Err(err) = self
+                .chain_state
+                .simulate_transaction(&versioned_transaction, params.preflight_commitment)
+            {
+                counter!("iris_error", "type" => "preflight_failure").increment(1);
+                return Err(invalid_request(&format!("preflight failed: {err}")));
+            }
+        }
+
+        let priority_fee = Self::priority_fee(&versioned_transaction);
+        if !self.pays_minimum_tip(&versioned_transaction) && priority_fee < self.minimum_tip() {
             counter!("iris_error", "type" => "no_tip_or_pays_less_than_minimum_tip").increment(1);
             return Err(invalid_request(
                 "no tip in the transaction or pays less than minimum tip",
@@ -207,16 +526,19 @@ impl IrisRpcServer for IrisRpcServerImpl {
         let signature = versioned_transaction.get_signature().to_string();
         info!("processing transaction with signature: {signature}");
         let slot = self.chain_state.get_slot();
+        let last_valid_block_height = self.last_valid_block_height(&versioned_transaction);
         let transaction = TransactionData::new(
             wire_transaction,
             versioned_transaction,
             slot,
+            last_valid_block_height,
+            priority_fee,
             params.max_retries.unwrap_or(self.max_retries as usize),
         );
         // add to store
         self.store.add_transaction(transaction.clone());
-        self.txn_sender
-            .send_transaction(transaction.wire_transaction);
+        self.sampler.record_submitted(1);
+        self.forwarder.forward(transaction.wire_transaction);
         Ok(signature)
     }
 
@@ -229,6 +551,10 @@ impl IrisRpcServer for IrisRpcServerImpl {
             counter!("iris_error", "type" => "batch_size_exceeded").increment(1);
             return Err(invalid_request("batch size exceeded"));
         }
+        if self.store.get_transactions().len() + batch.len() > self.max_queue_size() {
+            counter!("iris_error", "type" => "queue_full").increment(1);
+            return Err(queue_full());
+        }
         counter!("iris_txn_total_batches").increment(1);
         let mut wired_transactions = Vec::new();
         let mut signatures = Vec::new();
@@ -238,7 +564,7 @@ impl IrisRpcServer for IrisRpcServerImpl {
                 return Err(invalid_request("duplicate transaction"));
             }
             let encoding = params.encoding.unwrap_or(UiTransactionEncoding::Base58);
-            if !params.skip_preflight {
+            if !params.skip_preflight && !self.enable_preflight {
                 counter!("iris_error", "type" => "preflight_check").increment(1);
                 return Err(invalid_request("running preflight check is not supported"));
             }
@@ -258,12 +584,32 @@ impl IrisRpcServer for IrisRpcServerImpl {
                         return Err(invalid_request(&e.to_string()));
                     }
                 };
+            if !params.skip_preflight {
+                if let Err(err) = self
+                    .chain_state
+                    .simulate_transaction(&versioned_transaction, params.preflight_commitment)
+                {
+                    counter!("iris_error", "type" => "preflight_failure").increment(1);
+                    return Err(invalid_request(&format!("preflight failed: {err}")));
+                }
+            }
+            let priority_fee = Self::priority_fee(&versioned_transaction);
+            if !self.pays_minimum_tip(&versioned_transaction) && priority_fee < self.minimum_tip() {
+                counter!("iris_error", "type" => "no_tip_or_pays_less_than_minimum_tip")
+                    .increment(1);
+                return Err(invalid_request(
+                    "no tip in the transaction or pays less than minimum tip",
+                ));
+            }
             let signature = versioned_transaction.get_signature().to_string();
             let slot = self.chain_state.get_slot();
+            let last_valid_block_height = self.last_valid_block_height(&versioned_transaction);
             let transaction = TransactionData::new(
                 wire_transaction,
                 versioned_transaction,
                 slot,
+                last_valid_block_height,
+                priority_fee,
                 params.max_retries.unwrap_or(self.max_retries as usize),
             );
             // add to store
@@ -271,11 +617,16 @@ impl IrisRpcServer for IrisRpcServerImpl {
             wired_transactions.push(transaction.wire_transaction);
             signatures.push(signature);
         }
-        self.txn_sender.send_transaction_batch(wired_transactions);
+        self.sampler.record_submitted(signatures.len() as u64);
+        self.forwarder.forward_batch(wired_transactions);
         Ok(signatures)
     }
 
     async fn get_version(&self) -> RpcResult<VersionResponse> {
         Ok(self.version.clone())
     }
+
+    async fn get_tps(&self) -> RpcResult<TpsStats> {
+        Ok(self.sampler.snapshot())
+    }
 }